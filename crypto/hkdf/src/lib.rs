@@ -1,9 +1,25 @@
 //! This crate implements the [RFC5869] hash based key derivation function using [`bitcoin_hashes`].
 //!
+//! With the `zeroize` feature enabled, the internal pseudo random key is scrubbed from memory
+//! when an [`Hkdf`] is dropped, and [`Hkdf::derive_zeroizing`] is available to scrub derived key
+//! material too. With `alloc` additionally enabled, [`Hkdf::derive_zeroizing_vec`] supports
+//! runtime-determined output lengths.
+//!
+//! This crate is `no_std`, so it can be used in embedded and WASM contexts where the underlying
+//! [`bitcoin_hashes`] is usable without linking `std`.
+//!
+//! Note: [`TooLongError`] implements `core::error::Error`, which requires Rust 1.81 or newer.
+//!
 //! [RFC5869]: https://www.rfc-editor.org/rfc/rfc5869
 //! [`bitcoin_hashes`]: https://docs.rs/bitcoin_hashes/latest/bitcoin_hashes/
 
-use std::cmp::min;
+#![no_std]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+use core::cmp::min;
+use core::fmt;
 
 pub use bitcoin_hashes;
 pub use bitcoin_hashes::Hash as BitcoinHash;
@@ -19,6 +35,164 @@ pub mod hashes {
     pub use bitcoin_hashes::siphash24::Hash as Siphash24;
 }
 
+/// Error returned by [`expand`] when the requested output is longer than RFC5869 allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooLongError {
+    requested: usize,
+    max: usize,
+}
+
+impl fmt::Display for TooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "requested HKDF-expand output of {} bytes exceeds the maximum of {} (255*HashLength)",
+            self.requested, self.max
+        )
+    }
+}
+
+// Requires Rust 1.81+ for `core::error::Error` (stabilized without needing `std`).
+impl core::error::Error for TooLongError {}
+
+/// Run the standalone HKDF-Extract step, combining `salt` and `ikm` into a pseudo random key.
+///
+/// ## Inputs
+/// * `salt`: Optional salt value, if not required set to `&[0; H::LEN]`. As noted in the RFC
+///   the salt value can also be a secret.
+/// * `ikm`: Input keying material, secret key material our keys will be derived from
+pub fn extract<H: BitcoinHash>(salt: Option<&[u8]>, ikm: &[u8]) -> Hmac<H> {
+    // RFC5869's default salt is a string of `H::LEN` zero bytes. `H::LEN` is a hash-function
+    // associated const, not usable as an array length here, so for the common case feed the zero
+    // salt from a fixed stack-allocated zero block sized to the longest hash output this crate
+    // re-exports (SHA-512, at 64 bytes) instead of allocating a `Vec` for it.
+    //
+    // `BitcoinHash` is a public trait though, so a caller's own hash type can have `H::LEN > 64`;
+    // fall back to a heap-allocated zero buffer when `alloc` is available, or panic with an
+    // explicit message otherwise, rather than silently indexing out of the fixed buffer's bounds.
+    const ZERO_SALT: [u8; 64] = [0u8; 64];
+
+    let mut engine = match salt {
+        Some(salt) => HmacEngine::new(salt),
+        None if H::LEN <= ZERO_SALT.len() => HmacEngine::new(&ZERO_SALT[..H::LEN]),
+        #[cfg(feature = "alloc")]
+        None => HmacEngine::new(&alloc::vec![0u8; H::LEN]),
+        #[cfg(not(feature = "alloc"))]
+        None => panic!(
+            "extract::<H> with no salt requires H::LEN <= {} without the `alloc` feature (got H::LEN = {})",
+            ZERO_SALT.len(),
+            H::LEN,
+        ),
+    };
+    engine.input(ikm);
+    Hmac::from_engine(engine)
+}
+
+/// Run the standalone HKDF-Expand step, filling `okm` with key material derived from `prk`.
+///
+/// Unlike [`Hkdf::derive`], `okm` can be any runtime-determined length, not just a compile-time
+/// constant, which is useful when the amount of key material needed is only known at runtime.
+///
+/// ## Inputs
+/// * `prk`: The pseudo random key, e.g. the output of [`extract`].
+/// * `info`: Defines which key to derive. Different values lead to different keys.
+/// * `okm`: Output buffer to fill with derived key material. Note that
+///   `okm.len() <= H::LEN * 255` has to be true.
+///
+/// ## Errors
+/// Returns [`TooLongError`] if `okm.len() > H::LEN * 255`.
+pub fn expand<H: BitcoinHash>(
+    prk: &Hmac<H>,
+    info: &[u8],
+    okm: &mut [u8],
+) -> Result<(), TooLongError> {
+    let len = okm.len();
+    let iterations = expand_iterations::<H>(len)?;
+
+    for (iteration, block) in HkdfExpander::new(prk, info).take(iterations).enumerate() {
+        let current_slice = (H::LEN * iteration)..min(H::LEN * (iteration + 1), len);
+        let bytes_to_copy = current_slice.end - current_slice.start;
+        okm[current_slice].copy_from_slice(&block[0..bytes_to_copy]);
+    }
+
+    Ok(())
+}
+
+/// Number of `H::LEN`-sized blocks needed to cover `len` bytes of expand output, or
+/// [`TooLongError`] if that exceeds RFC5869's `255 * H::LEN` limit.
+///
+/// Callers that need to allocate a `len`-sized buffer before calling [`expand`] should run this
+/// check first, so an oversized `len` is rejected before the allocation is attempted.
+fn expand_iterations<H: BitcoinHash>(len: usize) -> Result<usize, TooLongError> {
+    // TODO: make const once rust allows
+    // `usize::is_multiple_of` isn't used here to avoid bumping the crate's MSRV further.
+    #[allow(clippy::manual_is_multiple_of)]
+    let iterations = if len % H::LEN == 0 {
+        len / H::LEN
+    } else {
+        len / H::LEN + 1
+    };
+
+    // Make sure we can cast iteration numbers to u8 later
+    if iterations > 255 {
+        return Err(TooLongError {
+            requested: len,
+            max: H::LEN * 255,
+        });
+    }
+
+    Ok(iterations)
+}
+
+/// Iterator over the successive HKDF-Expand output blocks `T(1), T(2), ...`.
+///
+/// Returned by [`Hkdf::expand_iter`], for streaming consumers that want key material lazily and
+/// without committing to a total output length up front: `take` exactly the number of blocks
+/// needed and copy the required bytes out of the last one. Yields `None` once the counter would
+/// exceed 255, the same `255 * H::LEN` limit [`expand`] enforces up front.
+pub struct HkdfExpander<'a, H: BitcoinHash> {
+    // The HMAC key (the PRK) is constant across all blocks, so we keep the midstate right after
+    // keying the engine (but before any message bytes are absorbed) and clone it for each block,
+    // instead of re-absorbing the key every time.
+    keyed_engine: HmacEngine<H>,
+    info: &'a [u8],
+    previous_block: Option<Hmac<H>>,
+    next_counter: u16,
+}
+
+impl<'a, H: BitcoinHash> HkdfExpander<'a, H> {
+    fn new(prk: &Hmac<H>, info: &'a [u8]) -> Self {
+        HkdfExpander {
+            keyed_engine: HmacEngine::<H>::new(&prk[..]),
+            info,
+            previous_block: None,
+            next_counter: 1,
+        }
+    }
+}
+
+impl<'a, H: BitcoinHash> Iterator for HkdfExpander<'a, H> {
+    type Item = Hmac<H>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_counter > 255 {
+            return None;
+        }
+
+        let mut engine = self.keyed_engine.clone();
+        if let Some(previous_block) = &self.previous_block {
+            engine.input(&previous_block[..]);
+        }
+        engine.input(self.info);
+        engine.input(&[self.next_counter as u8]);
+        let block = Hmac::from_engine(engine);
+
+        self.previous_block = Some(block);
+        self.next_counter += 1;
+        Some(block)
+    }
+}
+
 /// Implements the [RFC5869] hash based key derivation function using the hash function `H`.
 ///
 /// [RFC5869]: https://www.rfc-editor.org/rfc/rfc5869
@@ -35,11 +209,8 @@ impl<H: BitcoinHash> Hkdf<H> {
     /// * `salt`: Optional salt value, if not required set to `&[0; H::LEN]`. As noted in the RFC
     ///   the salt value can also be a secret.
     pub fn new(ikm: &[u8], salt: Option<&[u8]>) -> Self {
-        let mut engine = HmacEngine::new(salt.unwrap_or(&vec![0x00; H::LEN]));
-        engine.input(ikm);
-
         Hkdf {
-            prk: Hmac::from_engine(engine),
+            prk: extract(salt, ikm),
         }
     }
 
@@ -62,39 +233,9 @@ impl<H: BitcoinHash> Hkdf<H> {
     /// ## Panics
     /// If `LEN > H::LEN * 255`.
     pub fn derive<const LEN: usize>(&self, info: &[u8]) -> [u8; LEN] {
-        // TODO: make const once rust allows
-        let iterations = if LEN % H::LEN == 0 {
-            LEN / H::LEN
-        } else {
-            LEN / H::LEN + 1
-        };
-
-        // Make sure we can cast iteration numbers to u8 later
-        assert!(
-            iterations <= 255,
-            "RFC5869 only supports output length of up to 255*HashLength"
-        );
-
         let mut output = [0u8; LEN];
-        for iteration in 0..iterations {
-            let current_slice = (H::LEN * iteration)..min(H::LEN * (iteration + 1), LEN);
-            let last_slice = if iteration == 0 {
-                0..0
-            } else {
-                (H::LEN * (iteration - 1))..(H::LEN * iteration)
-            };
-
-            // TODO: re-use midstate
-            let mut engine = HmacEngine::<H>::new(&self.prk[..]);
-            engine.input(&output[last_slice]);
-            engine.input(info);
-            engine.input(&[(iteration + 1) as u8]);
-            let output_bytes = Hmac::from_engine(engine);
-
-            let bytes_to_copy = current_slice.end - current_slice.start;
-            output[current_slice].copy_from_slice(&output_bytes[0..bytes_to_copy]);
-        }
-
+        expand(&self.prk, info, &mut output)
+            .expect("RFC5869 only supports output length of up to 255*HashLength");
         output
     }
 
@@ -107,6 +248,65 @@ impl<H: BitcoinHash> Hkdf<H> {
         engine.input(&[1u8]);
         Hmac::from_engine(engine)
     }
+
+    /// Return an iterator over the HKDF-Expand output blocks for streaming consumers that want
+    /// key material lazily, without committing to a total output length up front.
+    ///
+    /// See [`HkdfExpander`] for details.
+    pub fn expand_iter<'b>(&self, info: &'b [u8]) -> HkdfExpander<'b, H> {
+        HkdfExpander::new(&self.prk, info)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: BitcoinHash> Hkdf<H> {
+    /// Like [`Hkdf::derive`], but returns the derived key material wrapped in
+    /// [`Zeroizing`](zeroize::Zeroizing) so
+    /// it is scrubbed from memory as soon as the caller drops it.
+    pub fn derive_zeroizing<const LEN: usize>(&self, info: &[u8]) -> zeroize::Zeroizing<[u8; LEN]> {
+        zeroize::Zeroizing::new(self.derive(info))
+    }
+}
+
+#[cfg(all(feature = "zeroize", feature = "alloc"))]
+impl<H: BitcoinHash> Hkdf<H> {
+    /// Like [`Hkdf::derive_zeroizing`], but for output lengths only known at runtime: derives
+    /// `len` bytes into a heap-allocated, zeroizing buffer.
+    ///
+    /// ## Errors
+    /// Returns [`TooLongError`] if `len > H::LEN * 255`.
+    pub fn derive_zeroizing_vec(
+        &self,
+        info: &[u8],
+        len: usize,
+    ) -> Result<zeroize::Zeroizing<alloc::vec::Vec<u8>>, TooLongError> {
+        // Validate `len` before allocating, so an oversized `len` (the exact case `TooLongError`
+        // exists for) returns an error instead of attempting a huge allocation.
+        expand_iterations::<H>(len)?;
+
+        let mut output = zeroize::Zeroizing::new(alloc::vec![0u8; len]);
+        expand(&self.prk, info, &mut output)?;
+        Ok(output)
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl<H: BitcoinHash> Drop for Hkdf<H> {
+    fn drop(&mut self) {
+        use zeroize::Zeroize;
+
+        // `Hmac<H>` doesn't expose a mutable byte accessor, so we scrub it in place through a raw
+        // byte view before it's deallocated. `BitcoinHash` is a public trait, so `H::LEN` is not
+        // trustworthy as the size of that view: a malicious or buggy `H` impl could report an
+        // `H::LEN` larger than `H`'s (and thus `Hmac<H>`'s) actual in-memory size, which would
+        // turn this into an out-of-bounds write. Use `size_of::<Hmac<H>>()` instead, which always
+        // matches the real layout of the value we're about to drop.
+        let len = core::mem::size_of::<Hmac<H>>();
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(&mut self.prk as *mut Hmac<H> as *mut u8, len)
+        };
+        bytes.zeroize();
+    }
 }
 
 #[cfg(test)]
@@ -128,6 +328,99 @@ mod tests {
         hkdf.derive::<16320>(&[]);
     }
 
+    #[test]
+    fn test_long_output_key_first_block_matches_derive_hmac() {
+        // Regression test for midstate reuse in `expand`: the first block of a long derivation
+        // must match `derive_hmac`, which only ever computes a single block.
+        let hkdf = Hkdf::<crate::hashes::Sha512>::new("foo".as_bytes(), None);
+        let long_output = hkdf.derive::<16320>(&[]);
+        let first_block = hkdf.derive_hmac(&[]);
+        assert_eq!(
+            &long_output[0..crate::hashes::Sha512::LEN],
+            &first_block[..]
+        );
+    }
+
+    #[test]
+    fn test_expand_iter_matches_derive() {
+        let hkdf = Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None);
+        let info = b"some info";
+
+        let derived: [u8; 100] = hkdf.derive(info);
+
+        let mut from_iter = [0u8; 100];
+        for (chunk, block) in from_iter
+            .chunks_mut(crate::hashes::Sha256::LEN)
+            .zip(hkdf.expand_iter(info))
+        {
+            chunk.copy_from_slice(&block[0..chunk.len()]);
+        }
+
+        assert_eq!(derived, from_iter);
+    }
+
+    #[test]
+    fn test_expand_iter_stops_after_255_blocks() {
+        let hkdf = Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None);
+        assert_eq!(hkdf.expand_iter(&[]).count(), 255);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_derive_zeroizing_matches_derive() {
+        let hkdf = Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None);
+        let info = b"some info";
+
+        let plain: [u8; 32] = hkdf.derive(info);
+        let zeroizing = hkdf.derive_zeroizing::<32>(info);
+
+        assert_eq!(plain, *zeroizing);
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_drop_scrubs_prk() {
+        // `Hkdf` isn't `Copy`, so we can't read its bytes after dropping it without `unsafe`. Wrap
+        // it in `ManuallyDrop` and call `ptr::drop_in_place` on its address directly, so the
+        // destructor runs in place rather than on a moved-from copy, then read the `prk` bytes
+        // through a raw pointer before and after, the same technique the `zeroize` crate's own
+        // test suite uses to assert scrubbing on `Drop`.
+        let mut hkdf =
+            core::mem::ManuallyDrop::new(Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None));
+        let len = core::mem::size_of_val(&*hkdf);
+        let ptr = &mut *hkdf as *mut Hkdf<crate::hashes::Sha256> as *mut u8;
+
+        let mut before = [0u8; 64];
+        before[..len].copy_from_slice(unsafe { core::slice::from_raw_parts(ptr, len) });
+        assert!(before[..len].iter().any(|&b| b != 0), "prk should not start zeroed");
+
+        unsafe { core::ptr::drop_in_place(ptr as *mut Hkdf<crate::hashes::Sha256>) };
+
+        let after = unsafe { core::slice::from_raw_parts(ptr, len) };
+        assert!(after.iter().all(|&b| b == 0), "prk should be zeroed after drop");
+    }
+
+    #[cfg(all(feature = "zeroize", feature = "alloc"))]
+    #[test]
+    fn test_derive_zeroizing_vec_matches_derive() {
+        let hkdf = Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None);
+        let info = b"some info";
+
+        let plain: [u8; 100] = hkdf.derive(info);
+        let zeroizing_vec = hkdf.derive_zeroizing_vec(info, 100).unwrap();
+
+        assert_eq!(&plain[..], &zeroizing_vec[..]);
+    }
+
+    #[cfg(all(feature = "zeroize", feature = "alloc"))]
+    #[test]
+    fn test_derive_zeroizing_vec_too_long_returns_err() {
+        // Regression test: an oversized `len` must return `TooLongError`, not abort the process
+        // by attempting to allocate a huge buffer.
+        let hkdf = Hkdf::<crate::hashes::Sha256>::new("foo".as_bytes(), None);
+        assert!(hkdf.derive_zeroizing_vec(&[], usize::MAX / 2).is_err());
+    }
+
     #[test]
     fn rfc5896_test_vector_1() {
         let input_key = [